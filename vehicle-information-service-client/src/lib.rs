@@ -3,23 +3,48 @@
 #![feature(await_macro, async_await, futures_api)]
 
 use futures::compat::*;
-use futures::StreamExt;
-use log::debug;
+use futures::future::BoxFuture;
+use futures::{FutureExt, SinkExt, StreamExt};
+use log::{debug, error};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json;
-use std::convert::Into;
+use std::collections::HashMap;
 use std::io;
-use std::sync::{Arc, Mutex};
-use tokio::prelude::{Sink, Stream};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::prelude::Stream as Stream01;
+use tokio::sync::{mpsc, oneshot};
+use tokio::timer::Delay;
+use tokio_codec::{Framed, LinesCodec};
 use tokio_tcp::TcpStream;
+use tokio_uds::UnixStream;
 use vehicle_information_service::api_type::*;
 use websocket::{ClientBuilder, OwnedMessage, WebSocketError};
 
+/// Reconnect attempts before a connection is given up on, used by
+/// `VISClient::connect`. Use `VISClient::connect_with_reconnect_limit` to
+/// override this.
+const DEFAULT_MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
 #[derive(Debug)]
 pub enum VISClientError {
     WebSocketError(WebSocketError),
     SerdeError(serde_json::Error),
     IoError(io::Error),
+    /// The connection was dropped. Returned both when reconnecting is
+    /// abandoned for good (the configured number of attempts was exceeded),
+    /// and when a request or subscription that was in flight didn't survive
+    /// a successful reconnect -- in the latter case the caller should just
+    /// retry.
+    ConnectionLost,
+    /// The VIS server rejected the request or subscription.
+    VisError {
+        number: i64,
+        reason: String,
+        message: String,
+    },
     Other,
 }
 
@@ -41,214 +66,1190 @@ impl From<io::Error> for VISClientError {
     }
 }
 
+/// One line of raw VIS JSON flowing to the server.
+type TransportSink = Pin<Box<dyn futures::Sink<String, Error = VISClientError> + Send>>;
+/// One line of raw VIS JSON arriving from the server.
+type TransportStream = Pin<Box<dyn futures::Stream<Item = Result<String, VISClientError>> + Send>>;
+
+/// Abstracts over how a `VISClient` talks to a VIS server, so the actor
+/// doesn't care whether it's plain WebSocket, secure WebSocket, or a local
+/// IPC socket underneath.
+trait Transport: Send + Sync {
+    fn connect(&self, address: String) -> BoxFuture<'static, Result<(TransportSink, TransportStream), VISClientError>>;
+}
+
+/// Plain, unencrypted WebSocket transport (`ws://`).
+struct WsTransport;
+
+impl Transport for WsTransport {
+    fn connect(&self, address: String) -> BoxFuture<'static, Result<(TransportSink, TransportStream), VISClientError>> {
+        async move {
+            let (client, _headers) = await!(ClientBuilder::new(&address)
+                .map_err(|_| VISClientError::Other)?
+                .async_connect_insecure()
+                .compat())?;
+            Ok(split_ws_client(client))
+        }
+            .boxed()
+    }
+}
+
+/// TLS-secured WebSocket transport (`wss://`).
+struct WssTransport;
+
+impl Transport for WssTransport {
+    fn connect(&self, address: String) -> BoxFuture<'static, Result<(TransportSink, TransportStream), VISClientError>> {
+        async move {
+            let (client, _headers) = await!(ClientBuilder::new(&address)
+                .map_err(|_| VISClientError::Other)?
+                .async_connect_secure(None)
+                .compat())?;
+            Ok(split_ws_client(client))
+        }
+            .boxed()
+    }
+}
+
+/// Splits a websocket client into a `TransportSink`/`TransportStream` pair,
+/// unwrapping `OwnedMessage::Text` frames down to the raw JSON they carry.
+/// Shared by the plain and TLS websocket transports, which only differ in
+/// how the socket itself is established.
+fn split_ws_client<S>(client: websocket::client::r#async::Client<S>) -> (TransportSink, TransportStream)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+{
+    let (sink, stream) = client.split();
+
+    let sink = sink
+        .sink_compat()
+        .sink_map_err(VISClientError::from)
+        .with(|text: String| futures::future::ok::<_, VISClientError>(OwnedMessage::Text(text)));
+
+    let stream = stream.compat().filter_map(|message| {
+        async move {
+            match message {
+                Ok(OwnedMessage::Text(text)) => Some(Ok(text)),
+                Ok(_) => None,
+                Err(e) => Some(Err(VISClientError::from(e))),
+            }
+        }
+    });
+
+    (Box::pin(sink), Box::pin(stream))
+}
+
+/// Local IPC transport over a Unix domain socket, carrying newline-delimited
+/// VIS JSON (`ipc://` scheme, or a bare filesystem path to the socket).
+struct IpcTransport;
+
+impl Transport for IpcTransport {
+    fn connect(&self, address: String) -> BoxFuture<'static, Result<(TransportSink, TransportStream), VISClientError>> {
+        async move {
+            let path = address.trim_start_matches("ipc://").to_string();
+            let unix_stream =
+                await!(UnixStream::connect(&path).compat()).map_err(VISClientError::from)?;
+            let (sink, stream) = Framed::new(unix_stream, LinesCodec::new()).split();
+
+            let sink = sink
+                .sink_compat()
+                .sink_map_err(|e| VISClientError::from(io::Error::new(io::ErrorKind::Other, e)));
+            let stream = stream
+                .compat()
+                .map(|result| result.map_err(|e| VISClientError::from(io::Error::new(io::ErrorKind::Other, e))));
+
+            Ok((Box::pin(sink) as TransportSink, Box::pin(stream) as TransportStream))
+        }
+            .boxed()
+    }
+}
+
+/// Picks the `Transport` implementation matching `address`'s scheme:
+/// `ws://` for plain WebSocket, `wss://` for TLS, and `ipc://`/a bare path
+/// for a local Unix-socket IPC connection.
+fn transport_for(address: &str) -> Box<dyn Transport> {
+    if address.starts_with("wss://") {
+        Box::new(WssTransport)
+    } else if address.starts_with("ws://") {
+        Box::new(WsTransport)
+    } else {
+        Box::new(IpcTransport)
+    }
+}
+
+/// A caller-stable identifier for a subscription, generated once by
+/// `subscribe`/`subscribe_raw` and held for the handle's whole lifetime.
+///
+/// Unlike the server-assigned `SubscriptionID`, this doesn't change when a
+/// dropped connection is transparently reestablished and the subscription is
+/// reissued under a new `SubscriptionID` -- which makes it the right key for
+/// `Instruction::Unsubscribe` to use. Reuses `ReqID`'s unique-value `Default`
+/// impl rather than inventing another id generator for the same purpose.
+type SubscriptionHandleId = ReqID;
+
+/// Work handed from a `VISClient` handle to the `ConnectionActor` that owns
+/// the websocket.
+enum Instruction {
+    /// A single request/response round-trip, e.g. `get`.
+    Request {
+        request_id: ReqID,
+        payload: String,
+        response: oneshot::Sender<Result<ActionSuccessResponse, VISClientError>>,
+    },
+    /// Subscribe to a path. Every `ActionSuccessResponse::Subscription` that
+    /// belongs to the `SubscriptionID` the server assigns this request is
+    /// forwarded on `sink` until the subscription is cancelled.
+    Subscribe {
+        request_id: ReqID,
+        handle_id: SubscriptionHandleId,
+        path: ActionPath,
+        filters: Option<Filters>,
+        sink: mpsc::UnboundedSender<Result<ActionSuccessResponse, VISClientError>>,
+        ack: oneshot::Sender<Result<SubscriptionID, VISClientError>>,
+    },
+    /// Cancel a live subscription, identified by the caller-stable handle id
+    /// rather than the server's `SubscriptionID` (which a reconnect may have
+    /// since reassigned underneath the caller).
+    Unsubscribe { handle_id: SubscriptionHandleId },
+}
+
+/// A VIS frame is either a success or an error response; which one only
+/// becomes apparent once it's parsed.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ActionResponse {
+    Success(ActionSuccessResponse),
+    Error(ActionErrorResponse),
+}
+
+/// Something the connection actor has to react to: a new instruction from a
+/// `VISClient` handle, a frame arriving from the server, the connection
+/// dropping, or every handle having been dropped.
+enum Event {
+    Instruction(Instruction),
+    Message(String),
+    Disconnected,
+    InstructionsClosed,
+}
+
+/// A subscription the actor has asked the server for, kept around so it can
+/// be reissued if the connection drops and is reestablished.
+struct PendingSubscription {
+    handle_id: SubscriptionHandleId,
+    path: ActionPath,
+    filters: Option<Filters>,
+    sink: mpsc::UnboundedSender<Result<ActionSuccessResponse, VISClientError>>,
+    /// Fired with the server-assigned `SubscriptionID` once this subscribe
+    /// request is acked. `None` for a subscription reissued after a
+    /// reconnect -- the caller already got their answer the first time.
+    ack: Option<oneshot::Sender<Result<SubscriptionID, VISClientError>>>,
+    /// Set when the caller unsubscribed while the `Action::Subscribe` for
+    /// this entry was still in flight to the server (only possible for a
+    /// post-reconnect reissue -- `ack: Some(_)` means the caller doesn't
+    /// have a `Subscription` handle to cancel yet). Once the ack arrives,
+    /// `handle_success` sends `Action::Unsubscribe` for the id it just
+    /// learned instead of keeping the subscription alive.
+    cancelled: bool,
+}
+
+/// Owns the transport and multiplexes every request and subscription issued
+/// by (possibly many) cloned `VISClient` handles over it.
+///
+/// This lets a single connection service many concurrent `get`s and hold
+/// many live subscriptions at once, instead of being consumed by the first
+/// call. It also reconnects transparently: a dropped connection doesn't kill
+/// a caller's subscription `Stream`, it's just reissued once the connection
+/// comes back.
+struct ConnectionActor {
+    max_reconnect_attempts: u32,
+    pending_requests: HashMap<ReqID, oneshot::Sender<Result<ActionSuccessResponse, VISClientError>>>,
+    pending_subscriptions: HashMap<ReqID, PendingSubscription>,
+    subscriptions: HashMap<SubscriptionID, PendingSubscription>,
+    /// Current `SubscriptionID` for each live subscription, keyed by its
+    /// caller-stable handle id. Updated every time a subscribe request (including
+    /// a post-reconnect reissue) is acked, so `Instruction::Unsubscribe` can
+    /// always find the subscription's up-to-date server-side id.
+    handle_ids: HashMap<SubscriptionHandleId, SubscriptionID>,
+}
+
+impl ConnectionActor {
+    fn new(max_reconnect_attempts: u32) -> Self {
+        Self {
+            max_reconnect_attempts,
+            pending_requests: HashMap::new(),
+            pending_subscriptions: HashMap::new(),
+            subscriptions: HashMap::new(),
+            handle_ids: HashMap::new(),
+        }
+    }
+
+    async fn run(
+        mut self,
+        transport: Arc<dyn Transport>,
+        server_address: String,
+        mut instructions: mpsc::UnboundedReceiver<Instruction>,
+        mut sink: TransportSink,
+        mut stream: TransportStream,
+    ) {
+        loop {
+            // Reissue every subscription that survived a previous connection
+            // (a no-op on the very first iteration, since there are none yet).
+            await!(self.resubscribe_all(&mut sink));
+
+            let instr_events = instructions
+                .by_ref()
+                .compat()
+                .filter_map(|instruction| {
+                    async move { instruction.ok().map(Event::Instruction) }
+                })
+                .chain(futures::stream::once(async { Event::InstructionsClosed }));
+            let msg_events = (&mut stream)
+                .map(|result| match result {
+                    Ok(text) => Event::Message(text),
+                    Err(e) => {
+                        debug!("VIS connection error: {:?}", e);
+                        Event::Disconnected
+                    }
+                })
+                .chain(futures::stream::once(async { Event::Disconnected }));
+            let mut events = futures::stream::select(instr_events.boxed(), msg_events.boxed());
+
+            let mut disconnected = false;
+            while let Some(event) = await!(events.next()) {
+                match event {
+                    Event::Instruction(instruction) => {
+                        await!(self.handle_instruction(instruction, &mut sink))
+                    }
+                    Event::Message(text) => {
+                        debug!("VIS Message {:#?}", text);
+                        match serde_json::from_str::<ActionResponse>(&text) {
+                            Ok(ActionResponse::Success(response)) => {
+                                await!(self.handle_success(response, &mut sink))
+                            }
+                            Ok(ActionResponse::Error(error)) => self.handle_error(error),
+                            Err(e) => debug!("Failed to deserialize VIS response: {}", e),
+                        }
+                    }
+                    Event::Disconnected => {
+                        disconnected = true;
+                        break;
+                    }
+                    Event::InstructionsClosed => break,
+                }
+            }
+
+            if !disconnected {
+                // The instructions channel closed: every `VISClient` handle
+                // was dropped, there's nothing left to serve.
+                return;
+            }
+
+            // Neither a `Request` nor an unacked subscribe survives this
+            // disconnect: fail them now rather than let them hang, whether or
+            // not the reconnect below succeeds. Already-acked subscriptions
+            // are different -- `resubscribe_all` reissues those transparently
+            // once reconnected.
+            self.fail_in_flight();
+
+            let (new_sink, new_stream) =
+                match await!(self.reconnect(&*transport, &server_address)) {
+                    Ok(pair) => pair,
+                    Err(e) => {
+                        error!("Giving up reconnecting to {}: {:?}", server_address, e);
+                        self.fail_all();
+                        return;
+                    }
+                };
+            sink = new_sink;
+            stream = new_stream;
+        }
+    }
+
+    async fn handle_instruction<Si>(&mut self, instruction: Instruction, sink: &mut Si)
+    where
+        Si: futures::Sink<String> + Unpin,
+    {
+        match instruction {
+            Instruction::Request {
+                request_id,
+                payload,
+                response,
+            } => {
+                self.pending_requests.insert(request_id, response);
+                if let Err(_) = await!(sink.send(payload)) {
+                    debug!("Failed to send VIS request");
+                }
+            }
+            Instruction::Subscribe {
+                request_id,
+                handle_id,
+                path,
+                filters,
+                sink: subscription_sink,
+                ack,
+            } => {
+                let subscribe = Action::Subscribe {
+                    path: path.clone(),
+                    filters: filters.clone(),
+                    request_id,
+                };
+                let payload =
+                    serde_json::to_string(&subscribe).expect("Failed to serialize subscribe");
+                self.pending_subscriptions.insert(
+                    request_id,
+                    PendingSubscription {
+                        handle_id,
+                        path,
+                        filters,
+                        sink: subscription_sink,
+                        ack: Some(ack),
+                        cancelled: false,
+                    },
+                );
+                if let Err(_) = await!(sink.send(payload)) {
+                    debug!("Failed to send VIS subscribe request");
+                }
+            }
+            Instruction::Unsubscribe { handle_id } => {
+                // `handle_ids` can be stale for a handle that's mid-reissue:
+                // `resubscribe_all` moves its entry from `subscriptions` into
+                // `pending_subscriptions` without touching `handle_ids`, so a
+                // hit here doesn't guarantee `subscriptions` still has it.
+                let live_subscription_id = self
+                    .handle_ids
+                    .remove(&handle_id)
+                    .filter(|subscription_id| self.subscriptions.contains_key(subscription_id));
+                if let Some(subscription_id) = live_subscription_id {
+                    self.subscriptions.remove(&subscription_id);
+                    let unsubscribe = Action::Unsubscribe { subscription_id };
+                    let payload = serde_json::to_string(&unsubscribe)
+                        .expect("Failed to serialize unsubscribe");
+                    if let Err(_) = await!(sink.send(payload)) {
+                        debug!("Failed to send VIS unsubscribe request");
+                    }
+                } else if let Some(pending) = self
+                    .pending_subscriptions
+                    .values_mut()
+                    .find(|pending| pending.handle_id == handle_id)
+                {
+                    // No live `SubscriptionID` for this handle -- it's
+                    // mid-reissue after a reconnect. The `Action::Subscribe`
+                    // is already in flight to the server, so it can't just be
+                    // forgotten here: mark it cancelled and let `handle_success`
+                    // unsubscribe it once its ack reveals the id to cancel.
+                    pending.cancelled = true;
+                }
+            }
+        }
+    }
+
+    /// Route a single successful inbound VIS frame to whichever caller is
+    /// waiting for it.
+    async fn handle_success<Si>(&mut self, response: ActionSuccessResponse, sink: &mut Si)
+    where
+        Si: futures::Sink<String> + Unpin,
+    {
+        match &response {
+            ActionSuccessResponse::Subscribe {
+                request_id,
+                subscription_id,
+                ..
+            } => {
+                if let Some(mut pending) = self.pending_subscriptions.remove(request_id) {
+                    if pending.cancelled {
+                        // The caller unsubscribed before this ack arrived;
+                        // the server doesn't know that yet, so tell it now
+                        // that we finally have the id to cancel.
+                        let unsubscribe = Action::Unsubscribe {
+                            subscription_id: *subscription_id,
+                        };
+                        let payload = serde_json::to_string(&unsubscribe)
+                            .expect("Failed to serialize unsubscribe");
+                        if let Err(_) = await!(sink.send(payload)) {
+                            debug!("Failed to send VIS unsubscribe request for a subscription cancelled before its ack arrived");
+                        }
+                        return;
+                    }
+                    if let Some(ack) = pending.ack.take() {
+                        let _ = ack.send(Ok(*subscription_id));
+                    }
+                    self.handle_ids.insert(pending.handle_id, *subscription_id);
+                    self.subscriptions.insert(*subscription_id, pending);
+                }
+                return;
+            }
+            ActionSuccessResponse::Subscription { subscription_id, .. } => {
+                if let Some(pending) = self.subscriptions.get(subscription_id) {
+                    let _ = pending.sink.unbounded_send(Ok(response));
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        let request_id = match &response {
+            ActionSuccessResponse::Get { request_id, .. } => Some(*request_id),
+            ActionSuccessResponse::Set { request_id, .. } => Some(*request_id),
+            ActionSuccessResponse::GetMetadata { request_id, .. } => Some(*request_id),
+            ActionSuccessResponse::Authorize { request_id, .. } => Some(*request_id),
+            ActionSuccessResponse::UnsubscribeAll { request_id, .. } => Some(*request_id),
+            _ => None,
+        };
+
+        if let Some(request_id) = request_id {
+            if let Some(response_tx) = self.pending_requests.remove(&request_id) {
+                let _ = response_tx.send(Ok(response));
+            }
+        }
+    }
+
+    /// Route a VIS error response to whichever caller is waiting for it,
+    /// instead of leaving it unhandled and the caller hanging forever.
+    fn handle_error(&mut self, error: ActionErrorResponse) {
+        let vis_error = VISClientError::VisError {
+            number: error.error.number,
+            reason: error.error.reason.clone(),
+            message: error.error.message.clone(),
+        };
+
+        if let Some(subscription_id) = error.subscription_id {
+            if let Some(pending) = self.subscriptions.remove(&subscription_id) {
+                let _ = pending.sink.unbounded_send(Err(vis_error));
+            }
+            return;
+        }
+
+        if let Some(request_id) = error.request_id {
+            if let Some(pending) = self.pending_subscriptions.remove(&request_id) {
+                if let Some(ack) = pending.ack {
+                    let _ = ack.send(Err(vis_error));
+                } else {
+                    let _ = pending.sink.unbounded_send(Err(vis_error));
+                }
+                return;
+            }
+            if let Some(response_tx) = self.pending_requests.remove(&request_id) {
+                let _ = response_tx.send(Err(vis_error));
+            }
+        }
+    }
+
+    /// Reissue every subscription that is still considered live, assigning it
+    /// a fresh `ReqID`. The server will hand back a new `SubscriptionID` in
+    /// its ack, which `handle_success` transparently folds back into
+    /// `subscriptions` -- the caller's `Stream` never notices.
+    async fn resubscribe_all<Si>(&mut self, sink: &mut Si)
+    where
+        Si: futures::Sink<String> + Unpin,
+    {
+        let live = std::mem::replace(&mut self.subscriptions, HashMap::new());
+        for (_, pending) in live {
+            let request_id = ReqID::default();
+            let subscribe = Action::Subscribe {
+                path: pending.path.clone(),
+                filters: pending.filters.clone(),
+                request_id,
+            };
+            let payload =
+                serde_json::to_string(&subscribe).expect("Failed to serialize subscribe");
+            self.pending_subscriptions.insert(request_id, pending);
+            if let Err(_) = await!(sink.send(payload)) {
+                debug!("Failed to resend subscribe request after reconnect");
+            }
+        }
+    }
+
+    /// Repeatedly try to reconnect via `transport`, backing off between
+    /// attempts, until either a connection succeeds or
+    /// `max_reconnect_attempts` is exceeded.
+    async fn reconnect(
+        &self,
+        transport: &dyn Transport,
+        server_address: &str,
+    ) -> Result<(TransportSink, TransportStream), VISClientError> {
+        let mut attempt: u32 = 0;
+        loop {
+            attempt += 1;
+            match await!(transport.connect(server_address.to_string())) {
+                Ok(pair) => {
+                    debug!(
+                        "Reconnected to {} after {} attempt(s)",
+                        server_address, attempt
+                    );
+                    return Ok(pair);
+                }
+                Err(e) => {
+                    debug!(
+                        "Reconnect attempt {}/{} to {} failed: {:?}",
+                        attempt, self.max_reconnect_attempts, server_address, e
+                    );
+                    if attempt >= self.max_reconnect_attempts {
+                        return Err(VISClientError::ConnectionLost);
+                    }
+                    let backoff = Duration::from_millis(200 * 2u64.pow(attempt.min(6)));
+                    let _ = await!(Delay::new(Instant::now() + backoff).compat());
+                }
+            }
+        }
+    }
+
+    /// Fail every request/subscribe that's in flight on the connection that
+    /// just dropped, so a caller waiting on it doesn't hang forever. Called
+    /// on every disconnect, not just a permanently failed reconnect: neither
+    /// `pending_requests` nor an unacked `pending_subscriptions` entry is
+    /// reissued on the new connection, so there's no other way their callers
+    /// find out.
+    fn fail_in_flight(&mut self) {
+        for (_, response) in self.pending_requests.drain() {
+            let _ = response.send(Err(VISClientError::ConnectionLost));
+        }
+        for (_, mut pending) in self.pending_subscriptions.drain() {
+            if let Some(ack) = pending.ack.take() {
+                let _ = ack.send(Err(VISClientError::ConnectionLost));
+            } else {
+                let _ = pending.sink.unbounded_send(Err(VISClientError::ConnectionLost));
+            }
+        }
+    }
+
+    /// The connection is permanently gone: tell every pending caller and
+    /// every live subscription so nobody waits forever.
+    fn fail_all(&mut self) {
+        self.fail_in_flight();
+        for (_, pending) in self.subscriptions.drain() {
+            let _ = pending.sink.unbounded_send(Err(VISClientError::ConnectionLost));
+        }
+        self.handle_ids.clear();
+    }
+}
+
+/// A live subscription returned by `subscribe`/`subscribe_raw`.
+///
+/// Wraps the value `Stream` together with the `SubscriptionID` the server
+/// assigned it and a way back to the connection actor. Dropping the handle,
+/// or calling `unsubscribe`, cancels the subscription on the server instead
+/// of leaving it running with nobody listening.
+pub struct Subscription<S> {
+    subscription_id: SubscriptionID,
+    handle_id: SubscriptionHandleId,
+    instructions: mpsc::UnboundedSender<Instruction>,
+    stream: S,
+    unsubscribed: bool,
+}
+
+impl<S> Subscription<S> {
+    /// The `SubscriptionID` the server assigned when this subscription was
+    /// created. Note that a transparent reconnect may reassign it under the
+    /// hood; this is meant for logging, not for matching up server frames --
+    /// `unsubscribe`/`Drop` use the stable handle id instead, precisely
+    /// because this one can go stale.
+    pub fn subscription_id(&self) -> SubscriptionID {
+        self.subscription_id
+    }
+
+    /// Cancel this subscription on the server.
+    pub async fn unsubscribe(mut self) {
+        self.send_unsubscribe();
+        self.unsubscribed = true;
+    }
+
+    fn send_unsubscribe(&self) {
+        let _ = self
+            .instructions
+            .unbounded_send(Instruction::Unsubscribe {
+                handle_id: self.handle_id,
+            });
+    }
+}
+
+impl<S> Drop for Subscription<S> {
+    fn drop(&mut self) {
+        if !self.unsubscribed {
+            self.send_unsubscribe();
+        }
+    }
+}
+
+impl<S> Stream01 for Subscription<S>
+where
+    S: Stream01,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Result<tokio::prelude::Async<Option<Self::Item>>, Self::Error> {
+        self.stream.poll()
+    }
+}
+
+/// A cheap, cloneable handle to a VIS connection.
+///
+/// All the transport state lives in a background `ConnectionActor`; cloning
+/// a `VISClient` just clones the channel used to talk to it, so many
+/// concurrent `get`s and subscriptions can share one underlying connection,
+/// and survive that connection being transparently reestablished.
+#[derive(Clone)]
 pub struct VISClient {
-    #[allow(dead_code)]
-    server_address: String,
-    client: websocket::client::r#async::Client<TcpStream>,
+    instructions: mpsc::UnboundedSender<Instruction>,
 }
 
 impl VISClient {
-    pub async fn connect(server_address: String) -> io::Result<Self> {
-        let (client, _headers) = await!(ClientBuilder::new(&server_address)
-            .unwrap()
-            .async_connect_insecure()
-            .compat())
-        .unwrap();
+    /// Connect to a VIS server. `server_address`'s scheme picks the
+    /// transport: `ws://` for plain WebSocket, `wss://` for TLS, and
+    /// `ipc://`/a bare filesystem path for a local Unix-socket IPC
+    /// connection to a co-located VIS daemon.
+    pub async fn connect(server_address: String) -> Result<Self, VISClientError> {
+        await!(Self::connect_with_reconnect_limit(
+            server_address,
+            DEFAULT_MAX_RECONNECT_ATTEMPTS
+        ))
+    }
+
+    /// Like `connect`, but with an explicit cap on how many times the actor
+    /// will retry a dropped connection before giving up on it for good.
+    pub async fn connect_with_reconnect_limit(
+        server_address: String,
+        max_reconnect_attempts: u32,
+    ) -> Result<Self, VISClientError> {
+        let transport: Arc<dyn Transport> = Arc::from(transport_for(&server_address));
+        let (sink, stream) = await!(transport.connect(server_address.clone()))?;
         debug!("Connected");
+
+        let (instructions_tx, instructions_rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(
+            ConnectionActor::new(max_reconnect_attempts)
+                .run(transport, server_address, instructions_rx, sink, stream)
+                .unit_error()
+                .boxed()
+                .compat(),
+        );
+
         Ok(Self {
-            server_address,
-            client,
+            instructions: instructions_tx,
         })
     }
 
     /// Retrieve vehicle signals.
-    pub async fn get<T>(self, path: ActionPath) -> io::Result<T>
+    pub async fn get<T>(&self, path: ActionPath) -> Result<T, VISClientError>
     where
         T: DeserializeOwned,
     {
         let request_id = ReqID::default();
         let get = Action::Get { path, request_id };
+        let payload = serde_json::to_string(&get).expect("Failed to serialize get");
 
-        let get_msg = serde_json::to_string(&get).expect("Failed to serialize get");
+        let (response_tx, response_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Request {
+                request_id,
+                payload,
+                response: response_tx,
+            })
+            .map_err(|_| VISClientError::ConnectionLost)?;
 
-        let (sink, stream) = self.client.split();
+        let response = await!(response_rx.compat())
+            .map_err(|_| VISClientError::ConnectionLost)??;
 
-        await!(sink.send(OwnedMessage::Text(get_msg)).compat()).expect("Failed to send message");
+        match response {
+            ActionSuccessResponse::Get { value, .. } => {
+                Ok(serde_json::from_value(value).expect("Failed to deserialize GET value"))
+            }
+            _ => unreachable!("connection actor routed a non-Get response to get()"),
+        }
+    }
 
-        let mut get_stream = stream
-            .filter_map(|msg| {
-                debug!("VIS Message {:#?}", msg);
+    /// Write a vehicle signal value.
+    pub async fn set<T>(&self, path: ActionPath, value: T) -> Result<(), VISClientError>
+    where
+        T: Serialize,
+    {
+        let request_id = ReqID::default();
+        let value = serde_json::to_value(value).expect("Failed to serialize set value");
+        let set = Action::Set {
+            path,
+            value,
+            request_id,
+        };
+        let payload = serde_json::to_string(&set).expect("Failed to serialize set");
 
-                if let OwnedMessage::Text(txt) = msg {
-                    let response = serde_json::from_str::<ActionSuccessResponse>(&txt)
-                        .expect("Failed to deserialize VIS response");
-                    if let ActionSuccessResponse::Get {
-                        request_id: resp_request_id,
-                        value,
-                        ..
-                    } = response
-                    {
-                        if request_id != resp_request_id {
-                            return None;
-                        }
+        let (response_tx, response_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Request {
+                request_id,
+                payload,
+                response: response_tx,
+            })
+            .map_err(|_| VISClientError::ConnectionLost)?;
 
-                        return serde_json::from_value(value)
-                            .expect("Failed to deserialize GET Value");
-                    }
-                    None
-                } else {
-                    None
-                }
+        let response = await!(response_rx.compat())
+            .map_err(|_| VISClientError::ConnectionLost)??;
+
+        match response {
+            ActionSuccessResponse::Set { .. } => Ok(()),
+            _ => unreachable!("connection actor routed a non-Set response to set()"),
+        }
+    }
+
+    /// Retrieve the VSS subtree schema/metadata for a path.
+    pub async fn get_metadata(&self, path: ActionPath) -> Result<serde_json::Value, VISClientError> {
+        let request_id = ReqID::default();
+        let get_metadata = Action::GetMetadata { path, request_id };
+        let payload =
+            serde_json::to_string(&get_metadata).expect("Failed to serialize getMetadata");
+
+        let (response_tx, response_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Request {
+                request_id,
+                payload,
+                response: response_tx,
             })
-            .compat();
+            .map_err(|_| VISClientError::ConnectionLost)?;
 
-        let get_response = await!(get_stream.next());
-        Ok(get_response.unwrap().unwrap())
+        let response = await!(response_rx.compat())
+            .map_err(|_| VISClientError::ConnectionLost)??;
+
+        match response {
+            ActionSuccessResponse::GetMetadata { metadata, .. } => Ok(metadata),
+            _ => unreachable!("connection actor routed a non-GetMetadata response to get_metadata()"),
+        }
+    }
+
+    /// Present a token to unlock access to restricted signals for the rest
+    /// of this connection. Usually called once, right after `connect`,
+    /// before any `get`/`subscribe` of a privileged path.
+    pub async fn authorize(&self, token: String) -> Result<(), VISClientError> {
+        let request_id = ReqID::default();
+        let authorize = Action::Authorize { token, request_id };
+        let payload = serde_json::to_string(&authorize).expect("Failed to serialize authorize");
+
+        let (response_tx, response_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Request {
+                request_id,
+                payload,
+                response: response_tx,
+            })
+            .map_err(|_| VISClientError::ConnectionLost)?;
+
+        let response = await!(response_rx.compat())
+            .map_err(|_| VISClientError::ConnectionLost)??;
+
+        match response {
+            ActionSuccessResponse::Authorize { .. } => Ok(()),
+            _ => unreachable!("connection actor routed a non-Authorize response to authorize()"),
+        }
     }
 
     /// Subscribe to the given path's vehicle signals.
-    /// This will return a stream containing all incoming values
+    /// Returns a `Subscription` handle wrapping a stream of every raw
+    /// `ActionSuccessResponse::Subscription` that arrives for it.
     pub async fn subscribe_raw(
-        self,
+        &self,
         path: ActionPath,
         filters: Option<Filters>,
-    ) -> impl Stream<Item = ActionSuccessResponse, Error = VISClientError> {
+    ) -> Result<Subscription<impl Stream01<Item = ActionSuccessResponse, Error = VISClientError>>, VISClientError>
+    {
         let request_id = ReqID::default();
-        let subscribe = Action::Subscribe {
-            path,
-            filters,
-            request_id,
-        };
+        let handle_id = SubscriptionHandleId::default();
 
-        let subscribe_msg =
-            serde_json::to_string(&subscribe).expect("Failed to serialize subscribe");
-
-        let (sink, stream) = self.client.split();
-
-        await!(sink.send(OwnedMessage::Text(subscribe_msg)).compat())
-            .expect("Failed to send message");
-        stream
-            .filter_map(|msg| {
-                debug!("VIS Message {:#?}", msg);
-                if let OwnedMessage::Text(txt) = msg {
-                    Some(
-                        serde_json::from_str::<ActionSuccessResponse>(&txt)
-                            .expect("Failed to deserialize VIS response"),
-                    )
-                } else {
-                    None
-                }
+        let (subscription_tx, subscription_rx) = mpsc::unbounded_channel();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Subscribe {
+                request_id,
+                handle_id,
+                path,
+                filters,
+                sink: subscription_tx,
+                ack: ack_tx,
             })
-            .map_err(Into::into)
+            .map_err(|_| VISClientError::ConnectionLost)?;
+
+        let subscription_id =
+            await!(ack_rx.compat()).map_err(|_| VISClientError::ConnectionLost)??;
+
+        let stream = subscription_rx
+            .map_err(|_| VISClientError::ConnectionLost)
+            .and_then(|result| result);
+
+        Ok(Subscription {
+            subscription_id,
+            handle_id,
+            instructions: self.instructions.clone(),
+            stream,
+            unsubscribed: false,
+        })
     }
 
     /// Subscribe to the given path's vehicle signals.
+    /// Returns a `Subscription` handle wrapping a stream of deserialized
+    /// values.
     pub async fn subscribe<T>(
-        self,
+        &self,
         path: ActionPath,
         filters: Option<Filters>,
-    ) -> impl Stream<Item = (SubscriptionID, T), Error = VISClientError>
+    ) -> Result<Subscription<impl Stream01<Item = (SubscriptionID, T), Error = VISClientError>>, VISClientError>
     where
         T: DeserializeOwned,
     {
-        let (sink, stream) = self.client.split();
+        let request_id = ReqID::default();
+        let handle_id = SubscriptionHandleId::default();
+
+        let (subscription_tx, subscription_rx) = mpsc::unbounded_channel();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Subscribe {
+                request_id,
+                handle_id,
+                path,
+                filters,
+                sink: subscription_tx,
+                ack: ack_tx,
+            })
+            .map_err(|_| VISClientError::ConnectionLost)?;
+
+        let subscription_id =
+            await!(ack_rx.compat()).map_err(|_| VISClientError::ConnectionLost)??;
+
+        let stream = subscription_rx
+            .map_err(|_| VISClientError::ConnectionLost)
+            .and_then(|result| result)
+            .filter_map(|response| {
+                if let ActionSuccessResponse::Subscription {
+                    subscription_id,
+                    value,
+                    ..
+                } = response
+                {
+                    let value = serde_json::from_value(value)
+                        .expect("Failed to deserialize subscription value");
+                    Some((subscription_id, value))
+                } else {
+                    None
+                }
+            });
 
+        Ok(Subscription {
+            subscription_id,
+            handle_id,
+            instructions: self.instructions.clone(),
+            stream,
+            unsubscribed: false,
+        })
+    }
+
+    /// Cancel every subscription on this connection.
+    pub async fn unsubscribe_all(&self) -> Result<(), VISClientError> {
         let request_id = ReqID::default();
-        let subscribe = Action::Subscribe {
-            path,
-            filters,
+        let unsubscribe_all = Action::UnsubscribeAll { request_id };
+        let payload =
+            serde_json::to_string(&unsubscribe_all).expect("Failed to serialize message");
+
+        let (response_tx, response_rx) = oneshot::channel();
+        self.instructions
+            .unbounded_send(Instruction::Request {
+                request_id,
+                payload,
+                response: response_tx,
+            })
+            .map_err(|_| VISClientError::ConnectionLost)?;
+
+        await!(response_rx.compat()).map_err(|_| VISClientError::ConnectionLost)??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn test_path() -> ActionPath {
+        "Vehicle.Test".into()
+    }
+
+    /// A subscribe ack should both unblock the caller's `ack` future and
+    /// record the server-assigned `SubscriptionID` under the subscription's
+    /// handle id, so later lookups by handle id find it.
+    #[test]
+    fn handle_success_folds_subscribe_ack_into_handle_and_subscriptions() {
+        let mut actor = ConnectionActor::new(5);
+        let handle_id = SubscriptionHandleId::default();
+        let request_id = ReqID::default();
+        let (subscription_tx, _subscription_rx) = mpsc::unbounded_channel();
+        let (ack_tx, ack_rx) = oneshot::channel();
+        actor.pending_subscriptions.insert(
             request_id,
-        };
+            PendingSubscription {
+                handle_id,
+                path: test_path(),
+                filters: None,
+                sink: subscription_tx,
+                ack: Some(ack_tx),
+                cancelled: false,
+            },
+        );
 
-        let subscribe_msg = serde_json::to_string(&subscribe).expect("Failed to serialize message");
+        let subscription_id = SubscriptionID::new(1);
+        let (mut sink, _rx) = futures::channel::mpsc::unbounded();
+        block_on(actor.handle_success(
+            ActionSuccessResponse::Subscribe {
+                request_id,
+                subscription_id,
+            },
+            &mut sink,
+        ));
 
-        await!(sink.send(OwnedMessage::Text(subscribe_msg)).compat())
-            .expect("Failed to send message");
+        match block_on(ack_rx.compat()).expect("ack sender dropped") {
+            Ok(acked_subscription_id) => assert_eq!(acked_subscription_id, subscription_id),
+            Err(e) => panic!("expected the subscribe to be acked, got {:?}", e),
+        }
+        assert_eq!(actor.handle_ids.get(&handle_id), Some(&subscription_id));
+        assert!(actor.subscriptions.contains_key(&subscription_id));
+    }
 
-        let subscription_id: Arc<Mutex<Option<SubscriptionID>>> = Default::default();
+    /// After a reconnect reissues a subscription under a new `SubscriptionID`,
+    /// the stable handle id must be remapped to it -- this is the bug the
+    /// maintainer reported: `unsubscribe`/`Drop` key off the handle id, so if
+    /// this remapping didn't happen they'd target a `SubscriptionID` the
+    /// server (and the actor) no longer know about.
+    #[test]
+    fn resubscribe_all_remaps_handle_id_to_new_subscription_id() {
+        let mut actor = ConnectionActor::new(5);
+        let handle_id = SubscriptionHandleId::default();
+        let old_subscription_id = SubscriptionID::new(1);
+        let (subscription_tx, _subscription_rx) = mpsc::unbounded_channel();
+        actor.subscriptions.insert(
+            old_subscription_id,
+            PendingSubscription {
+                handle_id,
+                path: test_path(),
+                filters: None,
+                sink: subscription_tx,
+                ack: None,
+                cancelled: false,
+            },
+        );
+        actor.handle_ids.insert(handle_id, old_subscription_id);
 
-        stream
-            .filter_map(move |msg| {
-                debug!("VIS Message {:#?}", msg);
+        let (mut sink, _rx) = futures::channel::mpsc::unbounded();
+        block_on(actor.resubscribe_all(&mut sink));
 
-                if let OwnedMessage::Text(txt) = msg {
-                    let action_success = serde_json::from_str::<ActionSuccessResponse>(&txt)
-                        .expect("Failed to deserialize VIS response");
+        assert!(actor.subscriptions.is_empty());
+        let reissued_request_id = *actor
+            .pending_subscriptions
+            .keys()
+            .next()
+            .expect("subscription was reissued");
 
-                    match action_success {
-                        ActionSuccessResponse::Subscribe {
-                            subscription_id: resp_subscription_id,
-                            request_id: resp_request_id,
-                            ..
-                        } => {
-                            // Make sure this is actually the response to our subscription request
-                            if resp_request_id != request_id {
-                                return None;
-                            }
-                            // Store subscription_id to make sure the stream only returns values based on this subscription
-                            *subscription_id.lock().unwrap() = Some(resp_subscription_id);
-                            return None;
-                        }
-                        ActionSuccessResponse::Subscription {
-                            subscription_id: resp_subscription_id,
-                            value,
-                            ..
-                        } => {
-                            if *subscription_id.lock().unwrap() != Some(resp_subscription_id) {
-                                return None;
-                            }
+        let new_subscription_id = SubscriptionID::new(2);
+        block_on(actor.handle_success(
+            ActionSuccessResponse::Subscribe {
+                request_id: reissued_request_id,
+                subscription_id: new_subscription_id,
+            },
+            &mut sink,
+        ));
 
-                            let stream_value = serde_json::from_value::<T>(value)
-                                .expect("Failed to deserialize subscription value");
-                            return Some((resp_subscription_id, stream_value));
-                        }
-                        _ => (),
-                    }
-                }
-                None
-            })
-            .map_err(Into::into)
+        assert_eq!(
+            actor.handle_ids.get(&handle_id),
+            Some(&new_subscription_id)
+        );
+        assert!(actor.subscriptions.contains_key(&new_subscription_id));
+        assert!(!actor.subscriptions.contains_key(&old_subscription_id));
     }
 
-    /// Subscribe to the given path's vehicle signals.
-    pub async fn unsubscribe_all<T>(self) -> impl Stream<Item = (), Error = VISClientError>
-    where
-        T: DeserializeOwned,
-    {
+    /// An `Unsubscribe` instruction carrying the handle id from before a
+    /// reconnect must still cancel the right (reassigned) subscription on the
+    /// server, not silently do nothing because the old `SubscriptionID` is no
+    /// longer in `subscriptions`.
+    #[test]
+    fn handle_instruction_unsubscribe_targets_current_subscription_id() {
+        let mut actor = ConnectionActor::new(5);
+        let handle_id = SubscriptionHandleId::default();
+        // The pre-reconnect `SubscriptionID` is intentionally absent from
+        // `actor.subscriptions`/`actor.handle_ids` below, standing in for
+        // the id a reconnect has already reassigned away from.
+        let new_subscription_id = SubscriptionID::new(2);
+        let (subscription_tx, _subscription_rx) = mpsc::unbounded_channel();
+        actor.subscriptions.insert(
+            new_subscription_id,
+            PendingSubscription {
+                handle_id,
+                path: test_path(),
+                filters: None,
+                sink: subscription_tx,
+                ack: None,
+                cancelled: false,
+            },
+        );
+        actor.handle_ids.insert(handle_id, new_subscription_id);
+
+        let (mut sink, mut rx) = futures::channel::mpsc::unbounded();
+        block_on(actor.handle_instruction(Instruction::Unsubscribe { handle_id }, &mut sink));
+
+        let sent = block_on(rx.next()).expect("unsubscribe payload was sent");
+        let sent: Action = serde_json::from_str(&sent).expect("sent payload is valid JSON");
+        match sent {
+            Action::Unsubscribe { subscription_id } => {
+                assert_eq!(subscription_id, new_subscription_id)
+            }
+            _ => panic!("expected an Unsubscribe action, got {:?}", sent),
+        }
+        assert!(!actor.subscriptions.contains_key(&new_subscription_id));
+        assert!(!actor.handle_ids.contains_key(&handle_id));
+    }
+
+    /// Unsubscribing while a post-reconnect reissue is still awaiting its ack
+    /// must not just stop tracking it locally -- the `Action::Subscribe` is
+    /// already in flight to the server, so once the ack reveals the id the
+    /// server assigned, the actor must cancel it there too instead of
+    /// leaking a subscription nobody is listening to any more.
+    #[test]
+    fn unsubscribe_during_pending_reissue_cancels_once_acked() {
+        let mut actor = ConnectionActor::new(5);
+        let handle_id = SubscriptionHandleId::default();
         let request_id = ReqID::default();
-        let unsubscribe_all = Action::UnsubscribeAll { request_id };
+        let (subscription_tx, _subscription_rx) = mpsc::unbounded_channel();
+        actor.pending_subscriptions.insert(
+            request_id,
+            PendingSubscription {
+                handle_id,
+                path: test_path(),
+                filters: None,
+                sink: subscription_tx,
+                ack: None,
+                cancelled: false,
+            },
+        );
 
-        let unsubscribe_all_msg =
-            serde_json::to_string(&unsubscribe_all).expect("Failed to serialize message");
+        let (mut sink, mut rx) = futures::channel::mpsc::unbounded();
+        block_on(actor.handle_instruction(Instruction::Unsubscribe { handle_id }, &mut sink));
 
-        let (sink, stream) = self.client.split();
-
-        await!(sink.send(OwnedMessage::Text(unsubscribe_all_msg)).compat())
-            .expect("Failed to send message");
-
-        stream
-            .filter_map(move |msg| {
-                debug!("VIS Message {:#?}", msg);
-
-                if let OwnedMessage::Text(txt) = msg {
-                    let action_success = serde_json::from_str::<ActionSuccessResponse>(&txt)
-                        .expect("Failed to deserialize VIS response");
-                    if let ActionSuccessResponse::UnsubscribeAll {
-                        request_id: resp_request_id,
-                        ..
-                    } = action_success
-                    {
-                        if resp_request_id != request_id {
-                            return None;
-                        }
+        assert!(
+            actor.pending_subscriptions.get(&request_id).unwrap().cancelled,
+            "unsubscribing a not-yet-acked reissue should mark it cancelled, not drop it"
+        );
 
-                        return Some(());
-                    }
-                    None
-                } else {
-                    None
-                }
-            })
-            .map_err(Into::into)
+        let subscription_id = SubscriptionID::new(3);
+        block_on(actor.handle_success(
+            ActionSuccessResponse::Subscribe {
+                request_id,
+                subscription_id,
+            },
+            &mut sink,
+        ));
+
+        let sent = block_on(rx.next()).expect("unsubscribe payload was sent once acked");
+        let sent: Action = serde_json::from_str(&sent).expect("sent payload is valid JSON");
+        match sent {
+            Action::Unsubscribe {
+                subscription_id: sent_subscription_id,
+            } => assert_eq!(sent_subscription_id, subscription_id),
+            _ => panic!("expected an Unsubscribe action, got {:?}", sent),
+        }
+        assert!(!actor.pending_subscriptions.contains_key(&request_id));
+        assert!(!actor.subscriptions.contains_key(&subscription_id));
+        assert!(!actor.handle_ids.contains_key(&handle_id));
+    }
+
+    /// After `resubscribe_all` moves a subscription from `subscriptions` into
+    /// `pending_subscriptions` under a fresh request id, `handle_ids` still
+    /// points at the now-gone old `SubscriptionID` until the reissue is
+    /// acked. Unsubscribing in that window must not be swallowed just
+    /// because `handle_ids` had a (stale) hit -- it has to fall through to
+    /// the same cancelled-pending-reissue path as a handle with no
+    /// `handle_ids` entry at all.
+    #[test]
+    fn unsubscribe_falls_back_to_pending_when_handle_ids_is_stale() {
+        let mut actor = ConnectionActor::new(5);
+        let handle_id = SubscriptionHandleId::default();
+        let old_subscription_id = SubscriptionID::new(1);
+        let request_id = ReqID::default();
+        let (subscription_tx, _subscription_rx) = mpsc::unbounded_channel();
+        actor.handle_ids.insert(handle_id, old_subscription_id);
+        actor.pending_subscriptions.insert(
+            request_id,
+            PendingSubscription {
+                handle_id,
+                path: test_path(),
+                filters: None,
+                sink: subscription_tx,
+                ack: None,
+                cancelled: false,
+            },
+        );
+
+        let (mut sink, mut rx) = futures::channel::mpsc::unbounded();
+        block_on(actor.handle_instruction(Instruction::Unsubscribe { handle_id }, &mut sink));
+
+        assert!(!actor.handle_ids.contains_key(&handle_id));
+        assert!(
+            actor.pending_subscriptions.get(&request_id).unwrap().cancelled,
+            "a stale handle_ids hit must not stop the pending reissue from being marked cancelled"
+        );
+
+        let new_subscription_id = SubscriptionID::new(2);
+        block_on(actor.handle_success(
+            ActionSuccessResponse::Subscribe {
+                request_id,
+                subscription_id: new_subscription_id,
+            },
+            &mut sink,
+        ));
+
+        let sent = block_on(rx.next()).expect("unsubscribe payload was sent once acked");
+        let sent: Action = serde_json::from_str(&sent).expect("sent payload is valid JSON");
+        match sent {
+            Action::Unsubscribe { subscription_id } => assert_eq!(subscription_id, new_subscription_id),
+            _ => panic!("expected an Unsubscribe action, got {:?}", sent),
+        }
+        assert!(!actor.pending_subscriptions.contains_key(&request_id));
+        assert!(!actor.subscriptions.contains_key(&new_subscription_id));
+    }
+
+    /// Dropping a `Subscription` handle cancels it server-side by sending an
+    /// `Unsubscribe` keyed on its handle id.
+    #[test]
+    fn subscription_drop_sends_unsubscribe_instruction() {
+        let (instructions_tx, mut instructions_rx) = mpsc::unbounded_channel();
+        let (_stream_tx, stream_rx) = mpsc::unbounded_channel::<()>();
+        let handle_id = SubscriptionHandleId::default();
+
+        let subscription = Subscription {
+            subscription_id: SubscriptionID::new(1),
+            handle_id,
+            instructions: instructions_tx,
+            stream: stream_rx,
+            unsubscribed: false,
+        };
+        drop(subscription);
+
+        let received = block_on(instructions_rx.compat().next())
+            .expect("Unsubscribe instruction was sent")
+            .expect("receiving the instruction should not error");
+        match received {
+            Instruction::Unsubscribe {
+                handle_id: sent_handle_id,
+            } => assert_eq!(sent_handle_id, handle_id),
+            _ => panic!("expected an Unsubscribe instruction"),
+        }
+    }
+
+    /// Once the connection actor is gone, calls on an existing `VISClient`
+    /// handle should report `ConnectionLost` instead of panicking.
+    #[test]
+    fn get_returns_connection_lost_once_actor_is_gone() {
+        let (instructions_tx, instructions_rx) = mpsc::unbounded_channel();
+        drop(instructions_rx);
+        let client = VISClient {
+            instructions: instructions_tx,
+        };
+
+        match block_on(client.get::<serde_json::Value>(test_path())) {
+            Err(VISClientError::ConnectionLost) => {}
+            other => panic!("expected ConnectionLost, got {:?}", other),
+        }
     }
 }